@@ -0,0 +1,152 @@
+//! A simplified FSST (Fast Static Symbol Table) string compressor: a table of up to 255
+//! variable-length byte strings (1-8 bytes each), trained once per index segment from a sample
+//! of values, is used to replace their occurrences with single-byte codes. Code `255` is
+//! reserved as an escape: it's followed by one literal byte, so any input byte that isn't part
+//! of a trained symbol can still be encoded. Unlike the paper's FSST, symbols here are picked
+//! in one greedy pass over raw substring frequency rather than iterative re-counting, which is
+//! enough for the repeated-label-value workload `fsst` targets (player names, event strings,
+//! openings) without the bookkeeping of the full algorithm.
+
+use std::collections::HashMap;
+
+/// Marks a literal byte that didn't match any trained symbol. The table holds at most 255
+/// symbols (codes `0..=254`), so this is never a valid symbol code.
+const ESCAPE: u8 = u8::MAX;
+
+/// The longest byte string a single symbol may encode.
+const MAX_SYMBOL_LEN: usize = 8;
+
+/// A trained set of up to 255 byte-string symbols, ordered so a symbol's index in `symbols` is
+/// its single-byte code.
+pub struct SymbolTable {
+    symbols: Vec<Vec<u8>>,
+}
+
+impl SymbolTable {
+    /// Trains a symbol table from a sample of strings: counts every substring of length 1 to
+    /// `MAX_SYMBOL_LEN` across `samples`, then greedily keeps the 255 substrings seen more than
+    /// once with the largest estimated savings (`occurrences * (length - 1)`, the bytes a single
+    /// code byte would replace). Length-1 substrings score zero, so they only fill slots longer
+    /// substrings leave free; each still saves one byte over the two-byte escape path.
+    pub fn train(samples: &[&str]) -> SymbolTable {
+        let mut counts: HashMap<&[u8], u32> = HashMap::new();
+        for sample in samples {
+            let bytes = sample.as_bytes();
+            for start in 0..bytes.len() {
+                let max_len = MAX_SYMBOL_LEN.min(bytes.len() - start);
+                for len in 1..=max_len {
+                    *counts.entry(&bytes[start..start + len]).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let mut candidates: Vec<(&[u8], u32)> =
+            counts.into_iter().filter(|(_, count)| *count > 1).collect();
+        candidates.sort_by_key(|(symbol, count)| {
+            std::cmp::Reverse(*count as usize * (symbol.len() - 1))
+        });
+
+        let mut symbols: Vec<Vec<u8>> = Vec::new();
+        for (symbol, _) in candidates {
+            if symbols.len() >= 255 {
+                break;
+            }
+            if symbols.iter().any(|existing| existing == symbol) {
+                continue;
+            }
+            symbols.push(symbol.to_vec());
+        }
+
+        SymbolTable { symbols }
+    }
+
+    /// Encodes `value` by greedily replacing the longest matching symbol at each position with
+    /// its code byte, falling back to an `ESCAPE`-prefixed literal byte where nothing matches.
+    pub fn compress(&self, value: &str) -> Vec<u8> {
+        let bytes = value.as_bytes();
+        let mut out = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            match self.longest_match(&bytes[i..]) {
+                Some((code, len)) => {
+                    out.push(code);
+                    i += len;
+                }
+                None => {
+                    out.push(ESCAPE);
+                    out.push(bytes[i]);
+                    i += 1;
+                }
+            }
+        }
+        out
+    }
+
+    /// Reverses `compress`, expanding each code byte back to its symbol (or, after an `ESCAPE`
+    /// byte, taking the following byte literally).
+    pub fn decompress(&self, codes: &[u8]) -> String {
+        let mut out = Vec::with_capacity(codes.len());
+        let mut i = 0;
+        while i < codes.len() {
+            if codes[i] == ESCAPE {
+                i += 1;
+                out.push(codes[i]);
+                i += 1;
+            } else {
+                out.extend_from_slice(&self.symbols[codes[i] as usize]);
+                i += 1;
+            }
+        }
+        String::from_utf8(out).expect("decompress reconstructs exactly the bytes compress read from a &str")
+    }
+
+    /// Finds the longest trained symbol that's a prefix of `remaining`, since the table is
+    /// small enough (<=255 entries) that a linear scan per position is cheap compared to the
+    /// bookkeeping of a trie.
+    fn longest_match(&self, remaining: &[u8]) -> Option<(u8, usize)> {
+        let max_len = remaining.len().min(MAX_SYMBOL_LEN);
+        for len in (1..=max_len).rev() {
+            if let Some(code) = self
+                .symbols
+                .iter()
+                .position(|symbol| symbol.len() == len && symbol.as_slice() == &remaining[..len])
+            {
+                return Some((code as u8, len));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrips_trained_and_untrained_values() {
+        let samples = ["Magnus Carlsen", "Magnus Carlsen", "Hikaru Nakamura", "Sicilian Defense"];
+        let table = SymbolTable::train(&samples);
+
+        for value in samples.iter().chain(["an unseen value with no symbols"].iter()) {
+            let compressed = table.compress(value);
+            assert_eq!(&table.decompress(&compressed), value);
+        }
+    }
+
+    #[test]
+    fn repeated_values_compress_smaller_than_raw() {
+        let samples = ["Sicilian Defense"; 20];
+        let table = SymbolTable::train(&samples);
+
+        let compressed = table.compress("Sicilian Defense");
+        assert!(compressed.len() < "Sicilian Defense".len());
+    }
+
+    #[test]
+    fn empty_table_falls_back_to_escapes() {
+        let table = SymbolTable::train(&[]);
+        let compressed = table.compress("abc");
+        assert_eq!(compressed, vec![ESCAPE, b'a', ESCAPE, b'b', ESCAPE, b'c']);
+        assert_eq!(table.decompress(&compressed), "abc");
+    }
+}
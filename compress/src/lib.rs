@@ -1,155 +1,410 @@
 #![allow(dead_code)]
 
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
 use bitpacking::{BitPacker4x, BitPacker};
 
+pub mod fsst;
+mod simd;
+mod vint;
+use vint::{decode_vint, encode_vint};
+
 const BLOCK_SIZE: usize = 128;
 
+/// Sentinel `StorageBlock::bits` value marking a VInt-encoded tail block, since `bits` is
+/// otherwise always < 32.
+const TAIL_SENTINEL: u8 = u8::MAX;
+
+/// Byte size of the `serialize`/`open` header: `BLOCK_SIZE` (u32), block count (u32), block
+/// index offset (u64).
+const HEADER_LEN: u64 = 16;
+
+/// A source `open` can lazily pull compressed block bytes from.
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// A decompressed block, aligned for `simd`'s `_mm_loadu_si128` loads and reused across
+/// `find`/`advance_to` calls.
+#[repr(align(16))]
+struct AlignedBuffer([u32; BLOCK_SIZE]);
+
 trait Storage {
     fn store(&mut self, value: u32);
     fn store_batch(&mut self, values: &[u32]);
 }
 
 /// Stores compressed blocks and their metadata
-struct CompressedStorage {
+pub struct CompressedStorage {
     bitpacker: BitPacker4x,
     blocks: Vec<StorageBlock>,
     compressed: Vec<u8>,
-    buffer: Vec<u32>, 
+    buffer: Vec<u32>,
+    /// The last value written so far (`0` before anything has been added); the next block's
+    /// delta-encoding base.
+    last: u32,
+    /// Scratch space a decompressed block is written into, reused across lookups.
+    scratch: AlignedBuffer,
+    /// Which block (if any) `scratch` currently holds, so repeated lookups into the same block
+    /// skip re-decompression.
+    cached_block: Option<usize>,
+    /// Set by `open`: the backing reader `compressed` is lazily loaded from, and the file
+    /// offset its block index starts at.
+    source: Option<(Box<dyn ReadSeek>, u64)>,
 }
 
 /// The metadata required to manage a compressed block
 struct StorageBlock {
-    /// The delta encoding of the initial element of the block
-    initial: u32,
-    /// The start index of compressed block inside the compressed storage 
+    /// The delta-encoding seed: the previous block's last value (`0` for the first block).
+    /// Blocks partition the value space into the half-open ranges `(base, next_block.base]`.
+    base: u32,
+    /// The start index of compressed block inside the compressed storage
     start: usize,
-    /// The number of bits that each element was compressed to
+    /// The number of bits that each element was compressed to, or `TAIL_SENTINEL` if this is
+    /// the trailing VInt-encoded block
     bits: u8,
+    /// The number of values held by this block (always `BLOCK_SIZE` except for a tail block).
+    count: u32,
+}
+
+impl Default for CompressedStorage {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CompressedStorage {
-    fn new() -> Self {
+    pub fn new() -> Self {
         Self {
             bitpacker: BitPacker4x::new(),
             blocks: Vec::new(),
             compressed: Vec::new(),
             buffer: Vec::with_capacity(BLOCK_SIZE),
+            last: 0,
+            scratch: AlignedBuffer([0u32; BLOCK_SIZE]),
+            cached_block: None,
+            source: None,
         }
     }
 
+    /// Writes the compressed block arena followed by a trailing block index and a small header
+    /// recording `BLOCK_SIZE`, the block count, and the index's offset.
+    fn serialize<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let index_offset = HEADER_LEN + self.compressed.len() as u64;
+
+        writer.write_all(&(BLOCK_SIZE as u32).to_le_bytes())?;
+        writer.write_all(&(self.blocks.len() as u32).to_le_bytes())?;
+        writer.write_all(&index_offset.to_le_bytes())?;
+
+        writer.write_all(&self.compressed)?;
+
+        for block in &self.blocks {
+            writer.write_all(&block.base.to_le_bytes())?;
+            writer.write_all(&(block.start as u64).to_le_bytes())?;
+            writer.write_all(&[block.bits])?;
+            writer.write_all(&block.count.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Opens a storage previously written with `serialize`. Only the header and block index are
+    /// read up front; the block arena is loaded lazily by `ensure_loaded`.
+    fn open<R: ReadSeek + 'static>(mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_LEN as usize];
+        reader.read_exact(&mut header)?;
+        let block_size = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+        debug_assert_eq!(block_size, BLOCK_SIZE);
+        let block_count = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        let index_offset = u64::from_le_bytes(header[8..16].try_into().unwrap());
+
+        reader.seek(SeekFrom::Start(index_offset))?;
+        let mut blocks = Vec::with_capacity(block_count);
+        let mut entry = [0u8; 17];
+        for _ in 0..block_count {
+            reader.read_exact(&mut entry)?;
+            blocks.push(StorageBlock {
+                base: u32::from_le_bytes(entry[0..4].try_into().unwrap()),
+                start: u64::from_le_bytes(entry[4..12].try_into().unwrap()) as usize,
+                bits: entry[12],
+                count: u32::from_le_bytes(entry[13..17].try_into().unwrap()),
+            });
+        }
+
+        // Appending further values after `open` isn't supported yet, so `last` is left at the
+        // default; only `new`-built storages use it.
+        Ok(Self {
+            bitpacker: BitPacker4x::new(),
+            blocks,
+            compressed: Vec::new(),
+            buffer: Vec::with_capacity(BLOCK_SIZE),
+            last: 0,
+            scratch: AlignedBuffer([0u32; BLOCK_SIZE]),
+            cached_block: None,
+            source: Some((Box::new(reader), index_offset)),
+        })
+    }
+
+    /// Loads the compressed block arena from `source` into `compressed`. A no-op once loaded, or
+    /// for storages built in-memory via `new`/`add`.
+    fn ensure_loaded(&mut self) -> io::Result<()> {
+        if !self.compressed.is_empty() {
+            return Ok(());
+        }
+        let Some((reader, index_offset)) = self.source.as_mut() else {
+            return Ok(());
+        };
+
+        let data_len = (*index_offset - HEADER_LEN) as usize;
+        reader.seek(SeekFrom::Start(HEADER_LEN))?;
+        let mut data = vec![0u8; data_len];
+        reader.read_exact(&mut data)?;
+        self.compressed = data;
+
+        Ok(())
+    }
+
     fn compress_buffer(&mut self) {
         // Ensure that the bufffer is full as only complete blocks can be compressed
         debug_assert_eq!(self.buffer.len(), BLOCK_SIZE);
 
-        // Find the initial value for delta encoding the first value
-        let initial = *self.buffer.get(0).expect("buffer[0] missing");
+        // Delta-encode against the previous block's last value (0 for the first block)
+        let base = self.last;
 
         // Calculate the number of bits and bytes of the compressed data
-        let bits = self.bitpacker.num_bits_sorted(initial, &self.buffer);
+        let bits = self.bitpacker.num_bits_sorted(base, &self.buffer);
         let bytes = BitPacker4x::BLOCK_LEN * (bits as usize) / 8;
 
-        // Compress 
+        // Compress
         let mut block = vec![0u8; bytes];
-        self.bitpacker.compress_sorted(initial, &self.buffer, &mut block, bits);
+        self.bitpacker.compress_sorted(base, &self.buffer, &mut block, bits);
 
         // Write the compressed block metadata to the block metadata store
         self.blocks.push(StorageBlock {
             bits,
-            initial,
+            base,
             start: self.compressed.len(),
+            count: BLOCK_SIZE as u32,
         });
 
         // Ensure we can recover the compressed block length from just the number of bits, given
         // the block size is fixed at 128 elements
         debug_assert_eq!(block.len(), bytes);
-       
+
         // Write the compressed block into the compressed store
         self.compressed.extend_from_slice(&block);
-        
-        let bitpacker = BitPacker4x::new();
-        let start = 100000;
-        let original: Vec<u32> = (start..start+896).filter(|i| i % 7 == 0).collect();
 
-        // Calculate the number of compressed bytes
-        let num_bits = bitpacker.num_bits_sorted(start, &original);
-        let compressed_bytes = BitPacker4x::BLOCK_LEN * (num_bits as usize) / 8;
+        self.last = *self.buffer.last().expect("buffer non-empty");
+        self.buffer.clear();
+    }
 
-        // Compress
-        let mut compressed = vec![0u8; compressed_bytes];
-        bitpacker.compress_sorted(start, &original, &mut compressed, num_bits);
+    /// Compresses whatever is left in `buffer` (fewer than `BLOCK_SIZE` values) as a VInt block,
+    /// since `compress_buffer`'s fixed-width bitpacking only works on full blocks.
+    fn compress_tail(&mut self) {
+        debug_assert!(!self.buffer.is_empty());
+        debug_assert!(self.buffer.len() < BLOCK_SIZE);
+
+        let base = self.last;
+        let start = self.compressed.len();
+
+        let mut prev = base;
+        for &value in &self.buffer {
+            encode_vint(value - prev, &mut self.compressed);
+            prev = value;
+        }
 
-        // Clear the buffer 
+        self.blocks.push(StorageBlock {
+            base,
+            start,
+            bits: TAIL_SENTINEL,
+            count: self.buffer.len() as u32,
+        });
+
+        self.last = *self.buffer.last().expect("buffer non-empty");
         self.buffer.clear();
     }
 
-    fn decompress_block(&self, block: usize) -> Vec<u32> {
-        let block = self.blocks.get(block).expect("block exists");
-        let bytes = BitPacker4x::BLOCK_LEN * (block.bits as usize) / 8;
-        let compressed = &self.compressed[block.start..block.start+bytes];
+    /// Flushes any buffered values that didn't fill a complete block, so they become visible
+    /// to `find`. Must be called once after the last `add`/`add_batch`; safe to call on an
+    /// already-empty buffer.
+    pub fn finalize(&mut self) {
+        if !self.buffer.is_empty() {
+            self.compress_tail();
+        }
+    }
+
+    /// Decompresses `block` into `self.scratch`, returning the number of valid elements written
+    /// (always `BLOCK_SIZE` except for a tail block).
+    fn decompress_into_scratch(&mut self, block_idx: usize) -> io::Result<usize> {
+        let block = self.blocks.get(block_idx).expect("block exists");
+        let count = if block.bits == TAIL_SENTINEL {
+            block.count as usize
+        } else {
+            BLOCK_SIZE
+        };
+
+        // scratch already holds this block's decompressed values
+        if self.cached_block == Some(block_idx) {
+            return Ok(count);
+        }
 
-        let mut decompressed = vec![0u32; 128];
-        self.bitpacker.decompress_sorted(block.initial, compressed, &mut decompressed, block.bits);
+        self.ensure_loaded()?;
+        let block = self.blocks.get(block_idx).expect("block exists");
+
+        if block.bits == TAIL_SENTINEL {
+            let mut offset = block.start;
+            let mut prev = block.base;
+            for i in 0..count {
+                let (delta, consumed) = decode_vint(&self.compressed[offset..]);
+                prev += delta;
+                self.scratch.0[i] = prev;
+                offset += consumed;
+            }
+        } else {
+            let bytes = BitPacker4x::BLOCK_LEN * (block.bits as usize) / 8;
+            let compressed = &self.compressed[block.start..block.start + bytes];
+            self.bitpacker
+                .decompress_sorted(block.base, compressed, &mut self.scratch.0, block.bits);
+        }
 
-        decompressed
+        self.cached_block = Some(block_idx);
+        Ok(count)
     }
 
-    fn add(&mut self, value: u32) {
+    pub fn add(&mut self, value: u32) {
         self.buffer.push(value);
         if self.buffer.len() == BLOCK_SIZE {
-            self.compress_buffer();             
+            self.compress_buffer();
         }
     }
 
-    fn add_batch(&mut self, values: &[u32]) {
+    pub fn add_batch(&mut self, values: &[u32]) {
         for value in values {
             self.add(*value);
         }
     }
 
-    /// Finds the block which must contain the the search value. 
-    /// As the blocks are totally ordered, this will always be the prior block to the first block
-    /// with a greater initial value than the search value.
-    /// The block is found using modified binary search in O(logN) time.
-    /// TODO: Write tests for edge cases
+    /// Binary-searches for the last block whose `base` is `< value` (or block 0 for `value == 0`,
+    /// since the first block's `base` sentinel is also `0`). `None` only if there are no blocks.
     fn find_block(&self, value: u32) -> Option<usize> {
         let mut left = 0;
         let mut right = self.blocks.len();
 
         while left < right {
             let mid = (left + right) / 2;
-            if self.blocks.get(mid).expect("mid block exists").initial < value + 1 {
+            if self.blocks.get(mid).expect("mid block exists").base < value {
                 left = mid + 1;
             } else {
                 right = mid;
             }
         }
-        let block = left-1;
+        if left == 0 {
+            return if self.blocks.is_empty() { None } else { Some(0) };
+        }
+        let block = left - 1;
 
         // Check that we haven't missed the search value by error
         let next = self.blocks.get(block+1);
-        debug_assert!(next.is_none() || next.unwrap().initial > value);
+        debug_assert!(next.is_none() || next.unwrap().base >= value);
 
         Some(block)
     }
 
-    /// Finds the value within the block.
-    /// This is achieved by a sequential scan but could be done using a binary search if that
-    /// proves to be faster for the 128-element blocks.
-    fn find_value(&self, value: u32, block: usize) -> Option<usize> {
-        let decompressed = self.decompress_block(block);
-        for (i, v) in decompressed.iter().enumerate() {
-            if v == &value {
-                return Some(block * BLOCK_SIZE + i)
+    /// The overall index of a block's first element, i.e. the sum of every earlier block's
+    /// `count`. Blocks aren't all `BLOCK_SIZE` long (a tail block can appear before more values
+    /// are `add`ed and `finalize`d again), so this can't just be `block * BLOCK_SIZE`.
+    fn block_start_index(&self, block: usize) -> usize {
+        self.blocks[..block].iter().map(|b| b.count as usize).sum()
+    }
+
+    /// Finds the value within the block, using a vectorized equality scan (`simd::find_eq`).
+    fn find_value(&mut self, value: u32, block: usize) -> io::Result<Option<usize>> {
+        let count = self.decompress_into_scratch(block)?;
+        let start = self.block_start_index(block);
+        Ok(simd::find_eq(&self.scratch.0[..count], value).map(|i| start + i))
+    }
+
+    /// Finds the first index within `block` whose value is `>= value` (`simd::find_ge`); backs
+    /// `advance_to` as well as range scans.
+    fn find_value_ge(&mut self, value: u32, block: usize) -> io::Result<Option<usize>> {
+        let count = self.decompress_into_scratch(block)?;
+        let start = self.block_start_index(block);
+        Ok(simd::find_ge(&self.scratch.0[..count], value).map(|i| start + i))
+    }
+
+    /// Checks if the value exists inside the compressed storage.
+    pub fn find(&mut self, value: u32) -> io::Result<Option<usize>> {
+        match self.find_block(value) {
+            None => Ok(None),
+            Some(block) => self.find_value(value, block),
+        }
+    }
+
+    /// Seeks the smallest stored value `>= target`, or `None` if every stored value is smaller.
+    /// The primitive `intersect` gallops on.
+    pub fn advance_to(&mut self, target: u32) -> io::Result<Option<u32>> {
+        let mut block = self.find_block(target).unwrap_or(0);
+
+        loop {
+            if block >= self.blocks.len() {
+                return Ok(None);
+            }
+            let count = self.decompress_into_scratch(block)?;
+            if let Some(i) = simd::find_ge(&self.scratch.0[..count], target) {
+                return Ok(Some(self.scratch.0[i]));
             }
+            block += 1;
         }
-        None
     }
+}
 
-    /// Checks if the value exists inside the compressed storage
-    fn find(&self, value: u32) -> Option<usize> {
-        match self.find_block(value) {
-            None => None,
-            Some(block) => self.find_value(value, block)
+/// Intersects several sorted posting lists using leap-frog (galloping) search: repeatedly
+/// advance every list that's behind the current maximum head, and emit a value once every
+/// head lands on it, built on top of `CompressedStorage::advance_to`.
+pub fn intersect(stores: &mut [&mut CompressedStorage]) -> io::Result<Vec<u32>> {
+    let mut result = Vec::new();
+    if stores.is_empty() {
+        return Ok(result);
+    }
+
+    let mut heads = Vec::with_capacity(stores.len());
+    for store in stores.iter_mut() {
+        match store.advance_to(0)? {
+            Some(value) => heads.push(value),
+            None => return Ok(result),
+        }
+    }
+
+    loop {
+        let max = *heads.iter().max().expect("heads is non-empty");
+        let mut all_agree = true;
+
+        for (store, head) in stores.iter_mut().zip(heads.iter_mut()) {
+            if *head < max {
+                match store.advance_to(max)? {
+                    Some(value) => *head = value,
+                    None => return Ok(result),
+                }
+            }
+            if *head != max {
+                all_agree = false;
+            }
+        }
+
+        if !all_agree {
+            continue;
+        }
+
+        result.push(max);
+
+        let next = match max.checked_add(1) {
+            Some(next) => next,
+            None => return Ok(result),
+        };
+        for (store, head) in stores.iter_mut().zip(heads.iter_mut()) {
+            match store.advance_to(next)? {
+                Some(value) => *head = value,
+                None => return Ok(result),
+            }
         }
     }
 }
@@ -165,11 +420,136 @@ mod tests {
         (100..1000)
             .filter(|i| i % 2 == 0)
             .for_each(|i| storage.add(i));
+        storage.finalize();
+
+        assert_eq!(storage.find(560).unwrap(), Some(230));
+        assert_eq!(storage.find(561).unwrap(), None);
+    }
+
+    #[test]
+    fn storage_tail() {
+        let mut storage = CompressedStorage::new();
 
-        assert_eq!(storage.find(560), Some(230));
-        assert_eq!(storage.find(561), None);
+        // 150 values: one full block of 128 plus a 22-element tail.
+        (0..150u32).for_each(|i| storage.add(i * 2));
+        storage.finalize();
+
+        // A value inside the tail block.
+        assert_eq!(storage.find(284).unwrap(), Some(142));
+        // The last value in the tail block.
+        assert_eq!(storage.find(298).unwrap(), Some(149));
+        assert_eq!(storage.find(299).unwrap(), None);
+    }
+
+    #[test]
+    fn find_index_is_correct_after_a_tail_block_is_followed_by_a_full_block() {
+        let mut storage = CompressedStorage::new();
+        (0..5u32).for_each(|i| storage.add(i));
+        storage.finalize();
+        (5..133u32).for_each(|i| storage.add(i));
+        storage.finalize();
+
+        assert_eq!(storage.find(10).unwrap(), Some(10));
     }
-    
+
+    #[test]
+    fn advance_to_skips_blocks_and_gaps() {
+        let mut storage = CompressedStorage::new();
+        (0..300u32).for_each(|i| storage.add(i * 2));
+        storage.finalize();
+
+        // Exact hit.
+        assert_eq!(storage.advance_to(560).unwrap(), Some(560));
+        // Land in a gap between stored values, inside a block.
+        assert_eq!(storage.advance_to(561).unwrap(), Some(562));
+        // Seek below everything.
+        assert_eq!(storage.advance_to(0).unwrap(), Some(0));
+        // Seek past the last value.
+        assert_eq!(storage.advance_to(10_000).unwrap(), None);
+    }
+
+    #[test]
+    fn intersect_finds_common_values() {
+        let mut a = CompressedStorage::new();
+        (0..500u32).for_each(|i| a.add(i));
+        a.finalize();
+
+        let mut b = CompressedStorage::new();
+        (0..500u32).filter(|i| i % 3 == 0).for_each(|i| b.add(i));
+        b.finalize();
+
+        let mut c = CompressedStorage::new();
+        (0..500u32).filter(|i| i % 5 == 0).for_each(|i| c.add(i));
+        c.finalize();
+
+        let result = intersect(&mut [&mut a, &mut b, &mut c]).unwrap();
+        let expected: Vec<u32> = (0..500).filter(|i| i % 15 == 0).collect();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn serialize_and_open_round_trip() {
+        let mut storage = CompressedStorage::new();
+        (0..300u32).for_each(|i| storage.add(i * 2));
+        storage.finalize();
+
+        let mut bytes = Vec::new();
+        storage.serialize(&mut bytes).unwrap();
+
+        let mut opened = CompressedStorage::open(std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(opened.find(560).unwrap(), Some(280));
+        assert_eq!(opened.find(561).unwrap(), None);
+        // The tail block lives past the end of a full 128-element block.
+        assert_eq!(opened.find(582).unwrap(), Some(291));
+    }
+
+    /// Compares bits-per-value for delta-encoding each block against its own first value vs.
+    /// against the previous block's last value (what `compress_buffer` now does).
+    #[test]
+    fn cross_block_delta_base_bits_per_value() {
+        let bitpacker = BitPacker4x::new();
+
+        // A deterministic stand-in for steadily increasing game ids
+        let mut id = 1_000_000u32;
+        let ids: Vec<u32> = (0u32..128 * 40)
+            .map(|i| {
+                id += 1 + i.wrapping_mul(2654435761) % 5;
+                id
+            })
+            .collect();
+
+        let mut own_first_bits = 0u64;
+        let mut prev_last_bits = 0u64;
+        let mut prev_last = 0u32;
+        for block in ids.chunks(BLOCK_SIZE) {
+            own_first_bits += bitpacker.num_bits_sorted(block[0], block) as u64;
+            prev_last_bits += bitpacker.num_bits_sorted(prev_last, block) as u64;
+            prev_last = *block.last().unwrap();
+        }
+
+        let blocks = (ids.len() / BLOCK_SIZE) as u64;
+        println!(
+            "own-first-value base: {:.2} bits/value ({} bits total over {} blocks)",
+            own_first_bits as f64 / ids.len() as f64,
+            own_first_bits,
+            blocks
+        );
+        println!(
+            "prev-block-last base: {:.2} bits/value ({} bits total over {} blocks)",
+            prev_last_bits as f64 / ids.len() as f64,
+            prev_last_bits,
+            blocks
+        );
+
+        // Functional correctness, regardless of which scheme compresses smaller
+        let mut storage = CompressedStorage::new();
+        storage.add_batch(&ids);
+        storage.finalize();
+        for (i, &value) in ids.iter().enumerate() {
+            assert_eq!(storage.find(value).unwrap(), Some(i));
+        }
+    }
+
     #[test]
     fn test() {
         let bitpacker = BitPacker4x::new();
@@ -0,0 +1,99 @@
+//! Vectorized search over a decompressed block, following the block-scan strategy tantivy's
+//! `BlockDecoder` uses on top of its `AlignedBuffer`: compare four lanes at a time with SSE2
+//! and fall back to a scalar scan on targets other than x86_64.
+
+/// Returns the index of the first element of `values` equal to `target`, if any.
+pub(crate) fn find_eq(values: &[u32], target: u32) -> Option<usize> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        unsafe { find_eq_sse2(values, target) }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        values.iter().position(|&v| v == target)
+    }
+}
+
+/// Returns the index of the first element of `values` that is `>= target`, if any. Relies on
+/// `values` being sorted ascending, which every `CompressedStorage` block is.
+pub(crate) fn find_ge(values: &[u32], target: u32) -> Option<usize> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        unsafe { find_ge_sse2(values, target) }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        values.iter().position(|&v| v >= target)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+unsafe fn find_eq_sse2(values: &[u32], target: u32) -> Option<usize> {
+    use std::arch::x86_64::*;
+
+    let needle = _mm_set1_epi32(target as i32);
+    let chunks = values.len() / 4;
+
+    for chunk in 0..chunks {
+        let offset = chunk * 4;
+        let lanes = _mm_loadu_si128(values.as_ptr().add(offset) as *const __m128i);
+        let eq = _mm_cmpeq_epi32(lanes, needle);
+        let mask = _mm_movemask_ps(_mm_castsi128_ps(eq));
+        if mask != 0 {
+            return Some(offset + mask.trailing_zeros() as usize);
+        }
+    }
+
+    (chunks * 4..values.len()).find(|&i| values[i] == target)
+}
+
+#[cfg(target_arch = "x86_64")]
+unsafe fn find_ge_sse2(values: &[u32], target: u32) -> Option<usize> {
+    use std::arch::x86_64::*;
+
+    // SSE2 only gives us a signed `>` compare, so bias both sides before comparing to treat
+    // the lanes as unsigned. The ids chidx stores (game ids, VInt-decoded deltas) never get
+    // close to i32::MAX in practice, but this keeps the comparison correct regardless.
+    let bias = _mm_set1_epi32(i32::MIN);
+    let needle = _mm_xor_si128(_mm_set1_epi32(target as i32), bias);
+    let chunks = values.len() / 4;
+
+    for chunk in 0..chunks {
+        let offset = chunk * 4;
+        let lanes = _mm_loadu_si128(values.as_ptr().add(offset) as *const __m128i);
+        let biased = _mm_xor_si128(lanes, bias);
+        let ge = _mm_or_si128(_mm_cmpgt_epi32(biased, needle), _mm_cmpeq_epi32(biased, needle));
+        let mask = _mm_movemask_ps(_mm_castsi128_ps(ge));
+        if mask != 0 {
+            return Some(offset + mask.trailing_zeros() as usize);
+        }
+    }
+
+    (chunks * 4..values.len()).find(|&i| values[i] >= target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eq_finds_first_match() {
+        let values: Vec<u32> = (0..200).collect();
+        assert_eq!(find_eq(&values, 130), Some(130));
+        assert_eq!(find_eq(&values, 500), None);
+    }
+
+    #[test]
+    fn eq_handles_lengths_not_a_multiple_of_four() {
+        let values: Vec<u32> = (0..22).collect();
+        assert_eq!(find_eq(&values, 21), Some(21));
+    }
+
+    #[test]
+    fn ge_finds_first_at_or_above() {
+        let values: Vec<u32> = (0..200).map(|i| i * 2).collect();
+        assert_eq!(find_ge(&values, 131), Some(66));
+        assert_eq!(find_ge(&values, 132), Some(66));
+        assert_eq!(find_ge(&values, 1000), None);
+    }
+}
@@ -0,0 +1,49 @@
+//! Variable-byte (VInt) encoding for `u32` values, used to compress the trailing partial
+//! block of a `CompressedStorage` that doesn't fill a full `BLOCK_SIZE` bitpacked block.
+//!
+//! Each value is split into 7-bit little-endian groups. The high bit of every byte except
+//! the last is set to mark that another byte follows.
+
+/// Appends the VInt encoding of `value` to `out`.
+pub(crate) fn encode_vint(mut value: u32, out: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Decodes a single VInt from the start of `bytes`, returning the value and the number of
+/// bytes consumed.
+pub(crate) fn decode_vint(bytes: &[u8]) -> (u32, usize) {
+    let mut value = 0u32;
+    let mut shift = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return (value, i + 1);
+        }
+        shift += 7;
+    }
+    panic!("truncated vint");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        for value in [0u32, 1, 127, 128, 300, 16384, u32::MAX] {
+            let mut bytes = Vec::new();
+            encode_vint(value, &mut bytes);
+            let (decoded, consumed) = decode_vint(&bytes);
+            assert_eq!(decoded, value);
+            assert_eq!(consumed, bytes.len());
+        }
+    }
+}
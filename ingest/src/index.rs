@@ -0,0 +1,519 @@
+//! Turns parsed PGN games into queryable position postings: replay each game's moves over a
+//! `Board`, hash the position reached after every ply, and record the game id against that
+//! hash in a `CompressedStorage` posting list. Querying a position is then just the existing
+//! `advance_to`/`intersect` machinery `compress` already provides for sorted id lists.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::OnceLock;
+
+use compress::CompressedStorage;
+
+use crate::lichess::Move;
+
+pub type GameId = u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Color {
+    White,
+    Black,
+}
+
+impl Color {
+    fn opposite(self) -> Color {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Piece {
+    Pawn,
+    Knight,
+    Bishop,
+    Rook,
+    Queen,
+    King,
+}
+
+impl Piece {
+    fn from_letter(c: char) -> Option<Piece> {
+        match c {
+            'N' => Some(Piece::Knight),
+            'B' => Some(Piece::Bishop),
+            'R' => Some(Piece::Rook),
+            'Q' => Some(Piece::Queen),
+            'K' => Some(Piece::King),
+            _ => None,
+        }
+    }
+}
+
+fn square(file: u8, rank: u8) -> usize {
+    rank as usize * 8 + file as usize
+}
+
+fn file_of(square: usize) -> u8 {
+    (square % 8) as u8
+}
+
+fn rank_of(square: usize) -> u8 {
+    (square / 8) as u8
+}
+
+/// A board replayed move by move from the PGN move list, just enough to compute a Zobrist hash
+/// after each ply. SAN resolution here is best-effort: en passant captures, and the rare case
+/// of two same-type pieces both satisfying a move's disambiguation hint, aren't handled, so a
+/// game with one of those stops being replayed (and indexed) at that point rather than risk
+/// hashing a board state we got wrong.
+struct Board {
+    squares: [Option<(Color, Piece)>; 64],
+    side_to_move: Color,
+}
+
+impl Board {
+    fn new() -> Self {
+        let mut squares = [None; 64];
+        let back_rank = [
+            Piece::Rook,
+            Piece::Knight,
+            Piece::Bishop,
+            Piece::Queen,
+            Piece::King,
+            Piece::Bishop,
+            Piece::Knight,
+            Piece::Rook,
+        ];
+        for (file, piece) in back_rank.into_iter().enumerate() {
+            squares[square(file as u8, 0)] = Some((Color::White, piece));
+            squares[square(file as u8, 1)] = Some((Color::White, Piece::Pawn));
+            squares[square(file as u8, 6)] = Some((Color::Black, Piece::Pawn));
+            squares[square(file as u8, 7)] = Some((Color::Black, piece));
+        }
+        Self {
+            squares,
+            side_to_move: Color::White,
+        }
+    }
+
+    /// Applies a single SAN move (e.g. `"Nf3"`, `"exd5"`, `"O-O"`, `"e8=Q"`) for the side to
+    /// move, returning `false` (and leaving the board untouched) if it can't be resolved.
+    fn apply_san(&mut self, san: &str) -> bool {
+        let color = self.side_to_move;
+        let trimmed = san.trim_end_matches(['+', '#', '!', '?']);
+        let applied = match trimmed {
+            "O-O" | "0-0" => self.castle(color, true),
+            "O-O-O" | "0-0-0" => self.castle(color, false),
+            _ => self.apply_normal_move(color, trimmed),
+        };
+        if applied {
+            self.side_to_move = color.opposite();
+        }
+        applied
+    }
+
+    fn castle(&mut self, color: Color, kingside: bool) -> bool {
+        let rank = match color {
+            Color::White => 0,
+            Color::Black => 7,
+        };
+        let king_from = square(4, rank);
+        let (king_to, rook_from, rook_to) = if kingside {
+            (square(6, rank), square(7, rank), square(5, rank))
+        } else {
+            (square(2, rank), square(0, rank), square(3, rank))
+        };
+        if self.squares[king_from] != Some((color, Piece::King))
+            || self.squares[rook_from] != Some((color, Piece::Rook))
+        {
+            return false;
+        }
+        self.squares[king_from] = None;
+        self.squares[rook_from] = None;
+        self.squares[king_to] = Some((color, Piece::King));
+        self.squares[rook_to] = Some((color, Piece::Rook));
+        true
+    }
+
+    fn apply_normal_move(&mut self, color: Color, san: &str) -> bool {
+        let (body, promotion) = match san.split_once('=') {
+            Some((body, letter)) => (body, letter.chars().next().and_then(Piece::from_letter)),
+            None => (san, None),
+        };
+        if body.len() < 2 {
+            return false;
+        }
+        let bytes = body.as_bytes();
+        let dest_file = bytes[bytes.len() - 2];
+        let dest_rank = bytes[bytes.len() - 1];
+        if !(b'a'..=b'h').contains(&dest_file) || !(b'1'..=b'8').contains(&dest_rank) {
+            return false;
+        }
+        let dest = square(dest_file - b'a', dest_rank - b'1');
+
+        let first = bytes[0];
+        let (piece, rest) = if first.is_ascii_uppercase() {
+            match Piece::from_letter(first as char) {
+                Some(piece) => (piece, &body[1..body.len() - 2]),
+                None => return false,
+            }
+        } else {
+            (Piece::Pawn, &body[..body.len() - 2])
+        };
+        let is_capture = rest.contains('x');
+        let hint = rest.trim_matches('x');
+        let hint_file = hint.bytes().find(|b| (b'a'..=b'h').contains(b));
+        let hint_rank = hint.bytes().find(|b| (b'1'..=b'8').contains(b));
+
+        if piece == Piece::Pawn && is_capture && self.squares[dest].is_none() {
+            // En passant: the captured pawn sits beside the capturing pawn, not on `dest`,
+            // which this board doesn't track well enough to resolve correctly (it would need
+            // to know the immediately preceding move). Bail out rather than silently leaving
+            // the captured pawn on the board as a phantom piece.
+            return false;
+        }
+
+        let origin = if piece == Piece::Pawn {
+            self.find_pawn_origin(color, dest, hint_file, is_capture)
+        } else {
+            self.find_origin(color, piece, dest, hint_file, hint_rank)
+        };
+        let Some(origin) = origin else {
+            return false;
+        };
+
+        self.squares[origin] = None;
+        self.squares[dest] = Some((color, promotion.unwrap_or(piece)));
+        true
+    }
+
+    /// Returns the one square holding a `color` `piece` that can reach `dest` and matches the
+    /// disambiguation hints, or `None` if zero or more than one candidate matches. `can_reach`
+    /// ignores pins and checks, so a tie here can mean "only one candidate is legally reachable,
+    /// the rest just share its geometry" as much as a genuinely ambiguous SAN move; either way
+    /// we're not confident enough in the result to apply it.
+    fn find_origin(
+        &self,
+        color: Color,
+        piece: Piece,
+        dest: usize,
+        hint_file: Option<u8>,
+        hint_rank: Option<u8>,
+    ) -> Option<usize> {
+        let mut candidates = (0..64).filter(|&candidate| {
+            self.squares[candidate] == Some((color, piece))
+                && hint_file.is_none_or(|f| file_of(candidate) == f - b'a')
+                && hint_rank.is_none_or(|r| rank_of(candidate) == r - b'1')
+                && self.can_reach(piece, candidate, dest)
+        });
+        let first = candidates.next()?;
+        if candidates.next().is_some() {
+            return None;
+        }
+        Some(first)
+    }
+
+    fn find_pawn_origin(
+        &self,
+        color: Color,
+        dest: usize,
+        hint_file: Option<u8>,
+        is_capture: bool,
+    ) -> Option<usize> {
+        let direction: i32 = match color {
+            Color::White => -1,
+            Color::Black => 1,
+        };
+        let dest_file = file_of(dest) as i32;
+        let dest_rank = rank_of(dest) as i32;
+
+        if is_capture {
+            let source_file = hint_file? as i32 - b'a' as i32;
+            let source_rank = dest_rank + direction;
+            if !(0..8).contains(&source_file) || !(0..8).contains(&source_rank) {
+                return None;
+            }
+            let candidate = square(source_file as u8, source_rank as u8);
+            return (self.squares[candidate] == Some((color, Piece::Pawn))).then_some(candidate);
+        }
+
+        for steps in [1, 2] {
+            let source_rank = dest_rank + steps * direction;
+            if !(0..8).contains(&source_rank) {
+                continue;
+            }
+            let candidate = square(dest_file as u8, source_rank as u8);
+            if self.squares[candidate] == Some((color, Piece::Pawn)) {
+                return Some(candidate);
+            }
+        }
+        None
+    }
+
+    /// Pseudo-legal reachability ignoring check: geometry plus, for sliding pieces, an empty
+    /// path. Good enough to disambiguate SAN moves, which already name a legal destination.
+    fn can_reach(&self, piece: Piece, from: usize, dest: usize) -> bool {
+        if from == dest {
+            return false;
+        }
+        let dx = file_of(dest) as i32 - file_of(from) as i32;
+        let dy = rank_of(dest) as i32 - rank_of(from) as i32;
+        match piece {
+            Piece::Knight => matches!((dx.abs(), dy.abs()), (1, 2) | (2, 1)),
+            Piece::King => dx.abs() <= 1 && dy.abs() <= 1,
+            Piece::Bishop => dx.abs() == dy.abs() && self.path_clear(from, dest, dx.signum(), dy.signum()),
+            Piece::Rook => (dx == 0 || dy == 0) && self.path_clear(from, dest, dx.signum(), dy.signum()),
+            Piece::Queen => {
+                (dx == 0 || dy == 0 || dx.abs() == dy.abs())
+                    && self.path_clear(from, dest, dx.signum(), dy.signum())
+            }
+            Piece::Pawn => false,
+        }
+    }
+
+    fn path_clear(&self, from: usize, dest: usize, step_file: i32, step_rank: i32) -> bool {
+        let mut file = file_of(from) as i32 + step_file;
+        let mut rank = rank_of(from) as i32 + step_rank;
+        let (dest_file, dest_rank) = (file_of(dest) as i32, rank_of(dest) as i32);
+        while (file, rank) != (dest_file, dest_rank) {
+            if self.squares[square(file as u8, rank as u8)].is_some() {
+                return false;
+            }
+            file += step_file;
+            rank += step_rank;
+        }
+        true
+    }
+}
+
+fn piece_index(color: Color, piece: Piece) -> usize {
+    let piece = match piece {
+        Piece::Pawn => 0,
+        Piece::Knight => 1,
+        Piece::Bishop => 2,
+        Piece::Rook => 3,
+        Piece::Queen => 4,
+        Piece::King => 5,
+    };
+    match color {
+        Color::White => piece,
+        Color::Black => piece + 6,
+    }
+}
+
+/// Random-looking but deterministic per-(square, piece, color) keys, plus one for the side to
+/// move. Built once with a splitmix64 generator rather than pulling in a `rand` dependency for
+/// a one-shot table fill; the actual bit patterns don't matter; only that they're well spread
+/// and stable across a process.
+struct ZobristTable {
+    squares: [[u64; 64]; 12],
+    side_to_move: u64,
+}
+
+fn zobrist_table() -> &'static ZobristTable {
+    static TABLE: OnceLock<ZobristTable> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut seed = 0x9E37_79B9_7F4A_7C15u64;
+        let mut next_key = || {
+            seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        };
+        let mut squares = [[0u64; 64]; 12];
+        for piece_keys in &mut squares {
+            for key in piece_keys {
+                *key = next_key();
+            }
+        }
+        ZobristTable {
+            squares,
+            side_to_move: next_key(),
+        }
+    })
+}
+
+fn zobrist_hash(board: &Board) -> u64 {
+    let table = zobrist_table();
+    let mut hash = 0u64;
+    for (square, occupant) in board.squares.iter().enumerate() {
+        if let Some((color, piece)) = occupant {
+            hash ^= table.squares[piece_index(*color, *piece)][square];
+        }
+    }
+    if board.side_to_move == Color::Black {
+        hash ^= table.side_to_move;
+    }
+    hash
+}
+
+/// Maps Zobrist position hashes to the sorted posting list of game ids that reach them.
+/// Mirrors `CompressedStorage`'s sorted-input assumption: games must be added in increasing
+/// `GameId` order.
+pub struct Index {
+    postings: HashMap<u64, CompressedStorage>,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Index {
+    pub fn new() -> Self {
+        Self {
+            postings: HashMap::new(),
+        }
+    }
+
+    /// Replays `moves` over a fresh board, hashing the position after every ply, and appends
+    /// `game_id` once to the posting list of each distinct position reached. Stops early (but
+    /// still indexes everything replayed so far) if a move can't be resolved, since every
+    /// position after an unresolved move would be built on a board we're no longer sure about.
+    pub fn add_game(&mut self, game_id: GameId, moves: &[Move<'_>]) {
+        let mut board = Board::new();
+        let mut positions = Vec::with_capacity(moves.len());
+        for mv in moves {
+            if !board.apply_san(mv.piece) {
+                break;
+            }
+            positions.push(zobrist_hash(&board));
+        }
+        positions.sort_unstable();
+        positions.dedup();
+        for hash in positions {
+            self.postings.entry(hash).or_default().add(game_id);
+        }
+    }
+
+    /// Returns every game id whose replay passed through `position_hash`.
+    pub fn query(&mut self, position_hash: u64) -> io::Result<Vec<GameId>> {
+        let Some(storage) = self.postings.get_mut(&position_hash) else {
+            return Ok(Vec::new());
+        };
+        storage.finalize();
+        collect_all(storage)
+    }
+
+    /// Returns the game ids common to every position in `position_hashes`, built on
+    /// `compress::intersect`'s leap-frog search. Temporarily removes the involved posting
+    /// lists from `postings` (reinserting them before returning) since the borrow checker
+    /// won't hand out more than one `&mut` into the map at a time otherwise.
+    pub fn query_all(&mut self, position_hashes: &[u64]) -> io::Result<Vec<GameId>> {
+        let mut removed = Vec::with_capacity(position_hashes.len());
+        for &hash in position_hashes {
+            match self.postings.remove(&hash) {
+                Some(storage) => removed.push((hash, storage)),
+                None => {
+                    for (hash, storage) in removed {
+                        self.postings.insert(hash, storage);
+                    }
+                    return Ok(Vec::new());
+                }
+            }
+        }
+
+        let mut stores = Vec::with_capacity(removed.len());
+        for (_, storage) in &mut removed {
+            storage.finalize();
+            stores.push(storage);
+        }
+        let result = compress::intersect(&mut stores);
+
+        for (hash, storage) in removed {
+            self.postings.insert(hash, storage);
+        }
+        result
+    }
+}
+
+/// Walks a whole posting list via `advance_to`, since `CompressedStorage` has no bulk iterator.
+fn collect_all(storage: &mut CompressedStorage) -> io::Result<Vec<GameId>> {
+    let mut results = Vec::new();
+    let mut cursor = 0u32;
+    while let Some(value) = storage.advance_to(cursor)? {
+        results.push(value);
+        match value.checked_add(1) {
+            Some(next) => cursor = next,
+            None => break,
+        }
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mv(piece: &'static str) -> Move<'static> {
+        Move {
+            number: "1",
+            dots: ".",
+            piece,
+            labels: Vec::new(),
+        }
+    }
+
+    fn hash_after(moves: &[&str]) -> u64 {
+        let mut board = Board::new();
+        for &san in moves {
+            assert!(board.apply_san(san), "failed to apply {san}");
+        }
+        zobrist_hash(&board)
+    }
+
+    #[test]
+    fn index_finds_games_sharing_a_position() {
+        let mut index = Index::new();
+        index.add_game(1, &[mv("e4"), mv("e5")]);
+        index.add_game(2, &[mv("e4"), mv("c5")]);
+
+        assert_eq!(index.query(hash_after(&["e4"])).unwrap(), vec![1, 2]);
+        assert_eq!(index.query(hash_after(&["e4", "e5"])).unwrap(), vec![1]);
+        assert_eq!(
+            index
+                .query_all(&[hash_after(&["e4"]), hash_after(&["e4", "e5"])])
+                .unwrap(),
+            vec![1]
+        );
+        assert_eq!(index.query(hash_after(&["d4"])).unwrap(), Vec::<GameId>::new());
+    }
+
+    #[test]
+    fn en_passant_capture_is_rejected_rather_than_corrupting_the_board() {
+        let mut board = Board::new();
+        for san in ["e4", "e6", "e5", "d5"] {
+            assert!(board.apply_san(san), "failed to apply {san}");
+        }
+        assert!(!board.apply_san("exd6"));
+        // The board must be left exactly as it was before the rejected move.
+        assert_eq!(board.squares[square(3, 4)], Some((Color::Black, Piece::Pawn))); // d5
+        assert_eq!(board.squares[square(4, 4)], Some((Color::White, Piece::Pawn))); // e5
+        assert_eq!(board.squares[square(3, 5)], None); // d6
+    }
+
+    #[test]
+    fn ambiguous_knight_move_is_rejected() {
+        let mut board = Board::new();
+        board.squares = [None; 64];
+        board.squares[square(3, 3)] = Some((Color::White, Piece::Knight)); // d4
+        board.squares[square(5, 3)] = Some((Color::White, Piece::Knight)); // f4
+        assert!(!board.apply_san("Ne6"));
+    }
+
+    #[test]
+    fn castling_and_knight_disambiguation_apply() {
+        let mut board = Board::new();
+        for san in ["Nf3", "Nc6", "g3", "Nf6", "Bg2", "d5", "O-O"] {
+            assert!(board.apply_san(san), "failed to apply {san}");
+        }
+        assert_eq!(board.squares[square(6, 0)], Some((Color::White, Piece::King)));
+        assert_eq!(board.squares[square(5, 0)], Some((Color::White, Piece::Rook)));
+        assert_eq!(board.squares[square(4, 0)], None);
+        assert_eq!(board.squares[square(7, 0)], None);
+    }
+}
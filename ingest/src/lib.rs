@@ -0,0 +1,3 @@
+pub mod index;
+pub mod lichess;
+pub mod stream;
@@ -9,20 +9,20 @@ use nom::{
 };
 
 #[derive(Debug)]
-struct Label<'a> {
-    key: &'a str,
-    value: &'a str,
+pub struct Label<'a> {
+    pub key: &'a str,
+    pub value: &'a str,
 }
 
 #[derive(Debug)]
-struct Move<'a> {
-    number: &'a str,
-    dots: &'a str,
-    piece: &'a str,
-    labels: Vec<Label<'a>>,
+pub struct Move<'a> {
+    pub number: &'a str,
+    pub dots: &'a str,
+    pub piece: &'a str,
+    pub labels: Vec<Label<'a>>,
 }
 
-fn parse_pgn(input: &str) -> IResult<&str, (Vec<Label>, Vec<Move>, &str)> {
+pub fn parse_pgn(input: &str) -> IResult<&str, (Vec<Label<'_>>, Vec<Move<'_>>, &str)> {
     let (input, _) = many0(char('\n'))(input)?;
     let (input, labels) = parse_labels(input)?;
     let (input, _) = many0(char('\n'))(input)?;
@@ -33,11 +33,11 @@ fn parse_pgn(input: &str) -> IResult<&str, (Vec<Label>, Vec<Move>, &str)> {
     Ok((input, (labels, moves, result)))
 }
 
-fn parse_labels(input: &str) -> IResult<&str, Vec<Label>> {
+fn parse_labels(input: &str) -> IResult<&str, Vec<Label<'_>>> {
     many0(parse_label)(input)
 }
 
-fn parse_label(input: &str) -> IResult<&str, Label> {
+fn parse_label(input: &str) -> IResult<&str, Label<'_>> {
     let (input, (key, value)) = terminated(
         delimited(char('['), tuple((label_key, label_value)), char(']')),
         char('\n'),
@@ -53,11 +53,11 @@ fn label_value(input: &str) -> IResult<&str, &str> {
     delimited(char('"'), take_while(|c: char| c != '"'), char('"'))(input)
 }
 
-fn parse_moves(input: &str) -> IResult<&str, Vec<Move>> {
+fn parse_moves(input: &str) -> IResult<&str, Vec<Move<'_>>> {
     many0(parse_move)(input)
 }
 
-fn parse_move(input: &str) -> IResult<&str, Move> {
+fn parse_move(input: &str) -> IResult<&str, Move<'_>> {
     let (input, (number, dots, piece, labels)) =
         tuple((move_number, move_dots, move_piece, move_labels))(input)?;
 
@@ -84,7 +84,7 @@ fn move_piece(input: &str) -> IResult<&str, &str> {
     delimited(space0, take_while(|c: char| c != ' '), space0)(input)
 }
 
-fn move_labels(input: &str) -> IResult<&str, Vec<Label>> {
+fn move_labels(input: &str) -> IResult<&str, Vec<Label<'_>>> {
     delimited(
         space0,
         delimited(char('{'), many0(move_label), char('}')),
@@ -92,7 +92,7 @@ fn move_labels(input: &str) -> IResult<&str, Vec<Label>> {
     )(input)
 }
 
-fn move_label(input: &str) -> IResult<&str, Label> {
+fn move_label(input: &str) -> IResult<&str, Label<'_>> {
     let (input, (key, value)) = delimited(
         space0,
         delimited(
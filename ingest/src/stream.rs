@@ -0,0 +1,225 @@
+//! Bounded-memory iteration over Lichess PGN databases, which ship as multi-gigabyte `.pgn`,
+//! `.pgn.gz` or `.pgn.zst` files: wrap the source in the matching decompressor, split the
+//! decoded byte stream into one buffer per game where the next game's tag section begins after
+//! movetext (see `GameSplitter`), and hand each
+//! buffer to `lichess::parse_pgn` in turn. `parse_pgn` only accepts a complete game (it's built
+//! from nom's `complete` combinators), so a full game always has to be buffered — but never
+//! more than one game at a time, unlike `include_str!`ing an entire database.
+//!
+//! `parse_pgn` borrows its `Label`/`Move` values out of the game buffer it's given, and that
+//! buffer only lives for one iteration of the splitter below, so the iterator can't yield those
+//! borrows out (a "yield a value that borrows from data owned by the iterator" lending pattern
+//! the standard `Iterator` trait can't express). Instead each game is copied into the owned
+//! `Game`/`OwnedLabel`/`OwnedMove` types below before being yielded.
+
+use std::io::{self, BufRead, BufReader, Lines, Read};
+
+use flate2::read::MultiGzDecoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+use crate::lichess::{self, Label, Move};
+
+/// Which decompressor to wrap the source reader in before splitting it into games.
+pub enum Compression {
+    /// Plain, already-decompressed PGN text.
+    None,
+    /// Gzip, Lichess's format for older monthly database dumps.
+    Gzip,
+    /// Zstandard, the format current Lichess monthly database dumps use.
+    Zstd,
+}
+
+/// An owned, `'static` copy of a parsed [`Label`], safe to yield out of the streaming iterator.
+#[derive(Debug, Clone)]
+pub struct OwnedLabel {
+    pub key: String,
+    pub value: String,
+}
+
+impl From<Label<'_>> for OwnedLabel {
+    fn from(label: Label<'_>) -> Self {
+        Self {
+            key: label.key.to_string(),
+            value: label.value.to_string(),
+        }
+    }
+}
+
+/// An owned, `'static` copy of a parsed [`Move`], safe to yield out of the streaming iterator.
+#[derive(Debug, Clone)]
+pub struct OwnedMove {
+    pub number: String,
+    pub dots: String,
+    pub piece: String,
+    pub labels: Vec<OwnedLabel>,
+}
+
+impl From<Move<'_>> for OwnedMove {
+    fn from(mv: Move<'_>) -> Self {
+        Self {
+            number: mv.number.to_string(),
+            dots: mv.dots.to_string(),
+            piece: mv.piece.to_string(),
+            labels: mv.labels.into_iter().map(OwnedLabel::from).collect(),
+        }
+    }
+}
+
+/// One fully parsed PGN game.
+#[derive(Debug, Clone)]
+pub struct Game {
+    pub labels: Vec<OwnedLabel>,
+    pub moves: Vec<OwnedMove>,
+    pub result: String,
+}
+
+/// Wraps `reader` in the decompressor matching `compression`, boxed so `GameSplitter` doesn't
+/// need to be generic over it.
+fn decompress<R: Read + 'static>(reader: R, compression: Compression) -> io::Result<Box<dyn Read>> {
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(ZstdDecoder::new(reader)?),
+    })
+}
+
+/// Splits a decoded PGN byte stream into one buffer per game. A game's tag section starts with
+/// `[`-prefixed lines, then a blank line, then the movetext; the next game's tags starting up
+/// again after movetext has begun is the reliable boundary (an internal blank line between tags
+/// and movetext doesn't end the game, since no movetext has been seen yet).
+struct GameSplitter<R: Read> {
+    lines: Lines<BufReader<R>>,
+    /// A tag line already read while looking for the previous game's end, carried over to
+    /// start the next game's buffer.
+    pending: Option<String>,
+}
+
+impl<R: Read> Iterator for GameSplitter<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buffer = String::new();
+        if let Some(carried) = self.pending.take() {
+            buffer.push_str(&carried);
+            buffer.push('\n');
+        }
+
+        let mut in_movetext = false;
+        loop {
+            match self.lines.next() {
+                None => {
+                    return if buffer.trim().is_empty() {
+                        None
+                    } else {
+                        Some(Ok(buffer))
+                    };
+                }
+                Some(Err(err)) => return Some(Err(err)),
+                Some(Ok(line)) => {
+                    if in_movetext && line.trim_start().starts_with('[') {
+                        self.pending = Some(line);
+                        return Some(Ok(buffer));
+                    }
+                    if !in_movetext && !line.trim().is_empty() && !line.trim_start().starts_with('[') {
+                        in_movetext = true;
+                    }
+                    buffer.push_str(&line);
+                    buffer.push('\n');
+                }
+            }
+        }
+    }
+}
+
+fn parse_game(text: &str) -> io::Result<Game> {
+    match lichess::parse_pgn(text) {
+        Ok((_, (labels, moves, result))) => Ok(Game {
+            labels: labels.into_iter().map(OwnedLabel::from).collect(),
+            moves: moves.into_iter().map(OwnedMove::from).collect(),
+            result: result.to_string(),
+        }),
+        Err(err) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("failed to parse PGN game: {err:?}"),
+        )),
+    }
+}
+
+/// Decodes `reader` (per `compression`) and yields its games one at a time, buffering at most
+/// one game's worth of text at once regardless of how large the overall source is.
+pub fn games<R: Read + 'static>(
+    reader: R,
+    compression: Compression,
+) -> io::Result<impl Iterator<Item = io::Result<Game>>> {
+    let decoded = decompress(reader, compression)?;
+    let splitter = GameSplitter {
+        lines: BufReader::new(decoded).lines(),
+        pending: None,
+    };
+    Ok(splitter.map(|record| parse_game(&record?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn splits_and_parses_multiple_games() {
+        let games: Vec<Game> = games(Cursor::new(sample_pgn()), Compression::None)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].labels[0].value, "A");
+        assert_eq!(games[0].result, "1-0");
+        assert_eq!(games[0].moves.len(), 2);
+        assert_eq!(games[1].labels[0].value, "B");
+        assert_eq!(games[1].result, "0-1");
+    }
+
+    fn sample_pgn() -> &'static str {
+        "[Event \"A\"]\n[Result \"1-0\"]\n\n\
+         1. e4 { [%clk 0:01:00] } 1... e5 { [%clk 0:01:00] } 1-0\n\n\
+         [Event \"B\"]\n[Result \"0-1\"]\n\n\
+         1. d4 { [%clk 0:01:00] } 1... d5 { [%clk 0:01:00] } 0-1\n"
+    }
+
+    #[test]
+    fn decodes_gzip_compressed_games() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(sample_pgn().as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let games: Vec<Game> = games(Cursor::new(compressed), Compression::Gzip)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].labels[0].value, "A");
+        assert_eq!(games[0].result, "1-0");
+        assert_eq!(games[1].labels[0].value, "B");
+        assert_eq!(games[1].result, "0-1");
+    }
+
+    #[test]
+    fn decodes_zstd_compressed_games() {
+        let compressed = zstd::stream::encode_all(sample_pgn().as_bytes(), 0).unwrap();
+
+        let games: Vec<Game> = games(Cursor::new(compressed), Compression::Zstd)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].labels[0].value, "A");
+        assert_eq!(games[0].result, "1-0");
+        assert_eq!(games[1].labels[0].value, "B");
+        assert_eq!(games[1].result, "0-1");
+    }
+}